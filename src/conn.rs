@@ -4,33 +4,109 @@ use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
 
-use crate::protocol::{Command, parse_command};
+use crate::irc;
+use crate::player::ConnId;
+use crate::protocol::{Command, IrcCommand, parse_command, parse_irc_command};
+use crate::room::RoomEvent;
 use crate::state::ServerState;
 use crate::{codegen, room::Room};
 
 
 struct ClientCtx {
+    /// Held so `Drop` can release this connection's room/nick reservations
+    /// no matter which path `handle` exits through.
+    state: ServerState,
+    /// Identifies this connection to the `PlayerRegistry`, distinct from
+    /// `nick` because several connections can share one nick.
+    conn_id: ConnId,
     nick: Option<String>,
     room_code: Option<String>,
-    room_rx: Option<broadcast::Receiver<String>>
+    room_rx: Option<broadcast::Receiver<RoomEvent>>,
+    /// Subscribed to this connection's nick's personal whisper channel once
+    /// a nick is set; carries fully-formatted `[dm] <from>: <text>` lines.
+    whisper_rx: Option<broadcast::Receiver<String>>,
+    /// Once set, this connection is driven by the IRC translation layer
+    /// instead of the native protocol (see `is_irc_user_line`).
+    irc_mode: bool,
+    irc_user_seen: bool,
+    irc_welcomed: bool
 }
 
 impl ClientCtx {
-    fn new() -> Self {
+    fn new(state: ServerState, conn_id: ConnId) -> Self {
         Self {
+            state,
+            conn_id,
             nick : None,
             room_code: None,
-            room_rx: None
+            room_rx: None,
+            whisper_rx: None,
+            irc_mode: false,
+            irc_user_seen: false,
+            irc_welcomed: false
         }
     }
 }
 
-pub async fn handle(state: ServerState, socket: TcpStream, peer: SocketAddr) -> Result<()> {
+impl Drop for ClientCtx {
+    /// Releases this connection's room membership, nick reservation, and
+    /// connected-clients gauge no matter how `handle` exits - clean EOF,
+    /// shutdown, or an I/O error bubbled up through `?` - so an abrupt
+    /// disconnect can never leave a nick reserved, a room occupied, or the
+    /// gauge drifted forever.
+    fn drop(&mut self) {
+        self.state.metrics.dec_connected();
+
+        if let Some(code) = self.room_code.take() {
+            if let Some(room) = self.state.get_room(&code) {
+                if let Some(nick) = &self.nick {
+                    room.leave(self.conn_id);
+                    room.announce(self.conn_id, format!("[server] {} left.", nick));
+                }
+
+                self.state.remove_if_empty(&code);
+            }
+        }
+
+        if let Some(nick) = self.nick.take() {
+            if self.state.players.release(&nick, self.conn_id) {
+                self.state.remove_whisper_channel(&nick);
+            }
+        }
+    }
+}
+
+/// The native protocol has no `USER` or `CAP` verb, and real IRC clients
+/// typically send `CAP LS`/`NICK` ahead of `USER` during handshake - so
+/// seeing `USER` or `CAP` is an unambiguous signal that the client is an
+/// IRC client, and `CAP` can flip the mode even before `USER` arrives.
+/// `NICK` is not a signal, since the native protocol uses it too.
+fn is_irc_user_line(line: &str) -> bool {
+    line.split_whitespace()
+        .next()
+        .map(|w| w.eq_ignore_ascii_case("USER") || w.eq_ignore_ascii_case("CAP"))
+        .unwrap_or(false)
+}
+
+/// What a dispatched command asks the connection loop to do next.
+enum Outcome {
+    Continue,
+    Disconnect
+}
+
+pub async fn handle(
+    state: ServerState,
+    socket: TcpStream,
+    peer: SocketAddr,
+    mut shutdown_rx: broadcast::Receiver<()>
+) -> Result<()> {
+    state.metrics.inc_connected();
+
     let (reader, mut writer) = socket.into_split();
 
     let mut lines = BufReader::new(reader).lines();
 
-    let mut ctx = ClientCtx::new();
+    let mut ctx = ClientCtx::new(state.clone(), state.next_conn_id());
 
     writer
         .write_all(b"Welcome to Relay!\nType HELP for commands\n")
@@ -38,116 +114,325 @@ pub async fn handle(state: ServerState, socket: TcpStream, peer: SocketAddr) ->
 
 
     loop {
-        if let Some(rx) = &mut ctx.room_rx {
-
-            tokio::select! {
-                // Branch A: Room broadcast received
-                result = rx.recv() => {
-                    match result {
-                        Ok(msg) => {
-                            writer.write_all(msg.as_bytes()).await?;
+        // Receives room broadcasts when in a room; otherwise never resolves,
+        // so the client-input and shutdown branches are still serviced.
+        let room_event = async {
+            match &mut ctx.room_rx {
+                Some(rx) => rx.recv().await,
+                None => std::future::pending().await
+            }
+        };
+
+        // Resolves when someone WHISPERs this connection's nick; otherwise
+        // never resolves, same trick as `room_event`.
+        let whisper_event = async {
+            match &mut ctx.whisper_rx {
+                Some(rx) => rx.recv().await,
+                None => std::future::pending().await
+            }
+        };
+
+        tokio::select! {
+            // Branch A: Room broadcast received
+            result = room_event => {
+                match result {
+                    Ok(event) => {
+                        // Real IRC servers don't echo a client's own
+                        // PRIVMSG/JOIN back through the channel relay - the
+                        // client already has its own confirmation (or, for
+                        // PRIVMSG, doesn't need one) - so skip messages this
+                        // same connection originated. Keyed by connection id,
+                        // not nick, since several connections can share a
+                        // nick and must each still see the others' messages.
+                        let is_self_echo = ctx.irc_mode && event.origin == ctx.conn_id;
+
+                        if !is_self_echo {
+                            let line = if ctx.irc_mode {
+                                let channel = ctx.room_code.clone().unwrap_or_default();
+                                irc::translate_broadcast(&event.text, &channel)
+                            } else {
+                                event.text
+                            };
+
+                            writer.write_all(line.as_bytes()).await?;
                             writer.write_all(b"\n").await?;
                         }
+                    }
 
-                        Err(broadcast::error::RecvError::Lagged(n)) => {
-                            // Client is too slow, skipped messages
-                            writer.write_all(
-                                format!("[server] Warning: skipped {} message\n", n).as_bytes()
-                            ).await?;
-                        }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        // Client is too slow, skipped messages
+                        writer.write_all(
+                            format!("[server] Warning: skipped {} message\n", n).as_bytes()
+                        ).await?;
+                    }
 
-                        Err(broadcast::error::RecvError::Closed) => {
-                            // Room channel closed (room was deleted)
-                            ctx.room_rx = None;
-                            writer.write_all(b"[server] Room closed\n").await?;
-                        }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        // Room channel closed (room was deleted)
+                        ctx.room_rx = None;
+                        writer.write_all(b"[server] Room closed\n").await?;
                     }
                 }
-                
-                // Branch B: Client send a line
-                line_result = lines.next_line() => {
-                    match line_result {
-                        Ok(Some(line)) => {
-                            let line = line.trim();
-                            if line.is_empty() {
-                                continue;
-                            }
-
-                            let cmd = match parse_command(line) {
-                                Ok(c) => c,
-                                Err(e) => {
-                                    writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
-                                    continue;
-                                }
-                            };
-
-                            if let Err(e) = handle_command(&state, &mut ctx, &mut writer, cmd).await {
-                                writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
-                            }
-                        }
+            }
 
-                        Ok(None) => {
-                            // Client disconnected (EOF)
+            // Branch B: Client sent a line
+            line_result = lines.next_line() => {
+                match line_result {
+                    Ok(Some(line)) => {
+                        if let Outcome::Disconnect = dispatch_line(&state, &mut ctx, &mut writer, &line).await? {
                             break;
                         }
-                        Err(e) => {
-                            return Err(e.into());
-                        }
                     }
-                }
 
+                    Ok(None) => {
+                        // Client disconnected (EOF)
+                        break;
+                    }
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                }
             }
-        } else {
-            // Not in a room - only handle client input (no broadcasts)
-            match lines.next_line().await {
-                Ok(Some(line)) => {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
+
+            // Branch C: Someone whispered this connection's nick
+            result = whisper_event => {
+                match result {
+                    Ok(msg) => {
+                        writer.write_all(msg.as_bytes()).await?;
+                        writer.write_all(b"\n").await?;
                     }
 
-                    let cmd = match parse_command(line) {
-                        Ok(c) => c,
-                        Err(e) => {
-                            writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
-                            continue;
-                        }
-                    };
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        // A few DMs were dropped under load; nothing to
+                        // recover, just keep listening.
+                    }
 
-                    if let Err(e) = handle_command(&state, &mut ctx, &mut writer, cmd).await {
-                        writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
+                    Err(broadcast::error::RecvError::Closed) => {
+                        ctx.whisper_rx = None;
                     }
                 }
+            }
 
-                Ok(None) => break,
-                Err(e) => return Err(e.into())
+            // Branch D: Server is shutting down
+            _ = shutdown_rx.recv() => {
+                writer.write_all(b"[server] server shutting down\n").await?;
+                break;
             }
         }
     }
 
-    // Cleanup on disconnect
-    if let Some(code) = ctx.room_code.take() {
-        if let Some(room) = state.get_room(&code) {
-            room.dec();
-            if let Some(nick) = &ctx.nick {
-                room.send(format!("[server] {} left.", nick));
+    // Room membership, the nick reservation, and the connected-clients gauge
+    // are released by `ClientCtx`'s `Drop` impl, which runs here regardless
+    // of how the loop above exited.
+    eprintln!("[{}] disconnected", peer);
+
+    Ok(())
+}
+
+/// Route one line of client input to the native or IRC command handler,
+/// picking up IRC mode the first time a `USER` or `CAP` line is seen.
+async fn dispatch_line(
+    state: &ServerState,
+    ctx: &mut ClientCtx,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    line: &str
+) -> Result<Outcome> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(Outcome::Continue);
+    }
+
+    if !ctx.irc_mode && is_irc_user_line(line) {
+        ctx.irc_mode = true;
+    }
+
+    if ctx.irc_mode {
+        return match handle_irc_command(state, ctx, writer, line).await {
+            Ok(outcome) => Ok(outcome),
+            Err(e) => {
+                writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
+                Ok(Outcome::Continue)
             }
+        };
+    }
+
+    let cmd = match parse_command(line) {
+        Ok(c) => c,
+        Err(e) => {
+            writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
+            return Ok(Outcome::Continue);
+        }
+    };
 
-            state.remove_if_empty(&code);
+    match handle_command(state, ctx, writer, cmd).await {
+        Ok(outcome) => Ok(outcome),
+        Err(e) => {
+            writer.write_all(format!("[error] {}\n", e).as_bytes()).await?;
+            Ok(Outcome::Continue)
         }
     }
+}
 
-    eprintln!("[{}] disconnected", peer);
+/// Send the `001` welcome numeric once both `NICK` and `USER` have arrived.
+async fn maybe_send_welcome(
+    ctx: &mut ClientCtx,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf
+) -> Result<(), String> {
+    if ctx.irc_welcomed {
+        return Ok(());
+    }
+
+    if let Some(nick) = &ctx.nick {
+        if ctx.irc_user_seen {
+            writer
+                .write_all(format!("{}\n", irc::welcome(nick)).as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+
+            ctx.irc_welcomed = true;
+        }
+    }
 
     Ok(())
 }
 
+async fn handle_irc_command(
+    state: &ServerState,
+    ctx: &mut ClientCtx,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    line: &str
+) -> Result<Outcome, String> {
+    let cmd = match parse_irc_command(line) {
+        Ok(c) => c,
+        // Unknown/unsupported IRC commands are ignored rather than
+        // surfaced as errors, matching how real IRC servers behave.
+        Err(_) => return Ok(Outcome::Continue)
+    };
+
+    match cmd {
+        IrcCommand::Nick(name) => {
+            state.players.reserve(&name, ctx.conn_id)?;
+
+            if let Some(old) = ctx.nick.take() {
+                // Same identity (only the case may differ): keep the reservation.
+                if old.to_lowercase() != name.to_lowercase()
+                    && state.players.release(&old, ctx.conn_id)
+                {
+                    state.remove_whisper_channel(&old);
+                }
+            }
+
+            ctx.whisper_rx = Some(state.ensure_whisper_channel(&name).subscribe());
+            ctx.nick = Some(name.clone());
+
+            if let Some(code) = &ctx.room_code {
+                if let Some(room) = state.get_room(code) {
+                    room.rename(ctx.conn_id, &name);
+                }
+            }
+
+            maybe_send_welcome(ctx, writer).await?;
+        }
+
+        IrcCommand::User(_username) => {
+            ctx.irc_user_seen = true;
+            maybe_send_welcome(ctx, writer).await?;
+        }
+
+        IrcCommand::Join(code) => {
+            let nick = ctx.nick.clone().ok_or("you must send NICK before JOIN")?;
+
+            if let Some(old_code) = ctx.room_code.take() {
+                if let Some(old_room) = state.get_room(&old_code) {
+                    old_room.leave(ctx.conn_id);
+                    old_room.announce(ctx.conn_id, format!("[server] {} left.", nick));
+                    state.remove_if_empty(&old_code)
+                }
+                ctx.room_rx = None;
+            }
+
+            let room = state.get_or_create_room(&code);
+
+            let (rx, history) = room.subscribe_with_history();
+            ctx.room_rx = Some(rx);
+
+            for line in history {
+                let rendered = irc::translate_broadcast(&line, &code);
+                writer
+                    .write_all(format!("{}\n", rendered).as_bytes())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
+            room.join(ctx.conn_id, &nick);
+            room.announce(ctx.conn_id, format!("[server] {} joined.", nick));
+            ctx.room_code = Some(code.clone());
+
+            writer
+                .write_all(format!("{}\n", irc::format_join(&nick, &code)).as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        IrcCommand::Privmsg(target, text) => {
+            let nick = ctx.nick.clone().ok_or("you must send NICK before PRIVMSG")?;
+
+            let room = state
+                .get_room(&target)
+                .ok_or(format!("no such channel: #{}", target))?;
+
+            room.send(ctx.conn_id, format!("{}: {}", nick, text));
+            state.metrics.inc_messages();
+        }
+
+        IrcCommand::Part(code) => {
+            if ctx.room_code.as_deref() == Some(code.as_str()) {
+                if let Some(nick) = ctx.nick.clone() {
+                    if let Some(room) = state.get_room(&code) {
+                        room.leave(ctx.conn_id);
+                        room.announce(ctx.conn_id, format!("[server] {} left.", nick));
+                        state.remove_if_empty(&code);
+                    }
+
+                    // Mirror Join's explicit confirmation: the broadcast
+                    // announce above is suppressed as self-echo, so without
+                    // this the parting client would never see its own PART.
+                    writer
+                        .write_all(format!("{}\n", irc::format_part(&nick, &code)).as_bytes())
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+
+                ctx.room_code = None;
+                ctx.room_rx = None;
+            }
+        }
+
+        IrcCommand::Quit => {
+            writer
+                .write_all(b"Goodbye.\n")
+                .await
+                .map_err(|e| e.to_string())?;
+
+            return Ok(Outcome::Disconnect);
+        }
+
+        IrcCommand::Ping(token) => {
+            writer
+                .write_all(format!("{}\n", irc::pong(&token)).as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(Outcome::Continue)
+}
+
 async fn handle_command(
     state: &ServerState,
     ctx: &mut ClientCtx,
     writer: &mut tokio::net::tcp::OwnedWriteHalf,
     cmd: crate::protocol::Command
-) -> Result<(), String> {
+) -> Result<Outcome, String> {
     match cmd {
         Command::Help => {
             writer
@@ -157,6 +442,9 @@ async fn handle_command(
                       CREATE        - create a new room\n\
                       JOIN <CODE>   - join an existing room\n\
                       MSG <text>    - send a message to your room\n\
+                      WHISPER <nick> <text> - send a private message to a user\n\
+                      LIST          - list active rooms and their occupancy\n\
+                      WHO [CODE]    - list nicknames in a room (default: your room)\n\
                       QUIT          - disconnect\n",
                 ).await
                 .map_err(|e| e.to_string())?;
@@ -168,16 +456,35 @@ async fn handle_command(
                 .await
                 .map_err(|e| e.to_string())?;
 
-            return Err("client quit".into());
+            return Ok(Outcome::Disconnect);
         }
 
         Command::Nick(name) => {
+            state.players.reserve(&name, ctx.conn_id)?;
+
+            if let Some(old) = ctx.nick.take() {
+                // Same identity (only the case may differ): keep the reservation.
+                if old.to_lowercase() != name.to_lowercase()
+                    && state.players.release(&old, ctx.conn_id)
+                {
+                    state.remove_whisper_channel(&old);
+                }
+            }
+
+            ctx.whisper_rx = Some(state.ensure_whisper_channel(&name).subscribe());
             ctx.nick = Some(name.clone());
+
+            if let Some(code) = &ctx.room_code {
+                if let Some(room) = state.get_room(code) {
+                    room.rename(ctx.conn_id, &name);
+                }
+            }
+
             writer
                 .write_all(format!("[ok] nickname set to '{}'\n", name).as_bytes())
                 .await
                 .map_err(|e| e.to_string())?;
-        }   
+        }
 
         Command::Create => {
             let nick = ctx.nick.clone().ok_or("set a nickname first: NICK <name>")?;
@@ -188,12 +495,21 @@ async fn handle_command(
 
             state.insert_room(code.clone(), room.clone());
 
-            room.inc();
+            room.join(ctx.conn_id, &nick);
 
             ctx.room_code = Some(code.clone());
-            ctx.room_rx = Some(room.subscribe());
 
-            room.send(format!("[server] {} joined", nick));
+            let (rx, history) = room.subscribe_with_history();
+            ctx.room_rx = Some(rx);
+
+            for line in history {
+                writer
+                    .write_all(format!("[history] {}\n", line).as_bytes())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
+            room.announce(ctx.conn_id, format!("[server] {} joined", nick));
 
             writer
                 .write_all(format!("[ok] room created: {}\n", code).as_bytes())
@@ -211,20 +527,27 @@ async fn handle_command(
             // Leave old room if any
             if let Some(old_code) = ctx.room_code.take() {
                 if let Some(old_room) = state.get_room(&old_code) {
-                    old_room.dec();
-                    old_room.send(format!("[server] {} left.", nick));
+                    old_room.leave(ctx.conn_id);
+                    old_room.announce(ctx.conn_id, format!("[server] {} left.", nick));
                     state.remove_if_empty(&old_code)
                 }
                 ctx.room_rx = None;
             }
 
-            // Subscribe to new room broadcasts
-            let rx = room.subscribe();
+            // Subscribe to new room broadcasts, snapshotting history atomically
+            let (rx, history) = room.subscribe_with_history();
             ctx.room_rx = Some(rx);
 
+            for line in history {
+                writer
+                    .write_all(format!("[history] {}\n", line).as_bytes())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
             // Join new room
-            room.inc();
-            room.send(format!("[server] {} joined.", nick));
+            room.join(ctx.conn_id, &nick);
+            room.announce(ctx.conn_id, format!("[server] {} joined.", nick));
             ctx.room_code = Some(code.clone());
 
             writer 
@@ -241,9 +564,55 @@ async fn handle_command(
                 .get_room(code)
                 .ok_or("room no longer exists")?;
 
-            room.send(format!("{}: {}", nick, text));
+            room.send(ctx.conn_id, format!("{}: {}", nick, text));
+            state.metrics.inc_messages();
+        }
+
+        Command::Whisper(target, text) => {
+            let nick = ctx.nick.clone().ok_or("set a nickname first: NICK <name>")?;
+
+            let tx = state
+                .whisper_sender(&target)
+                .ok_or(format!("no such user: {}", target))?;
+
+            let _ = tx.send(format!("[dm] {}: {}", nick, text));
+        }
+
+        Command::List => {
+            let rooms = state.list_rooms();
+
+            if rooms.is_empty() {
+                writer
+                    .write_all(b"[list] no active rooms\n")
+                    .await
+                    .map_err(|e| e.to_string())?;
+            } else {
+                for (code, count) in rooms {
+                    writer
+                        .write_all(format!("[list] {} ({} users)\n", code, count).as_bytes())
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        Command::Who(code) => {
+            let code = code
+                .or_else(|| ctx.room_code.clone())
+                .ok_or("join a room first or specify: WHO <CODE>")?;
+
+            let room = state
+                .get_room(&code)
+                .ok_or(format!("no such room: {}", code))?;
+
+            for nick in room.members() {
+                writer
+                    .write_all(format!("[who] {}: {}\n", code, nick).as_bytes())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
         }
     }
 
-    Ok(())
+    Ok(Outcome::Continue)
 }
\ No newline at end of file
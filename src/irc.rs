@@ -0,0 +1,60 @@
+//! IRC line-protocol translation layer.
+//!
+//! Lets standard IRC clients (WeeChat, HexChat, ...) talk to `relay`
+//! alongside the native `NICK`/`CREATE`/`JOIN`/`MSG` protocol. A connection
+//! picks its dialect from the first lines it sends: seeing a `USER`
+//! command means the client is speaking IRC, since the native protocol has
+//! no such verb. See `conn::handle` for where that detection happens.
+
+/// Render a chat message as a `PRIVMSG` line real IRC clients will display.
+pub fn format_privmsg(nick: &str, channel: &str, text: &str) -> String {
+    format!(":{nick}!{nick}@relay PRIVMSG #{channel} :{text}")
+}
+
+pub fn format_join(nick: &str, channel: &str) -> String {
+    format!(":{nick}!{nick}@relay JOIN #{channel}")
+}
+
+pub fn format_part(nick: &str, channel: &str) -> String {
+    format!(":{nick}!{nick}@relay PART #{channel}")
+}
+
+/// The `001` welcome numeric, sent once a connection has supplied both
+/// `NICK` and `USER`.
+pub fn welcome(nick: &str) -> String {
+    format!(":relay 001 {nick} :Welcome to Relay, {nick}")
+}
+
+pub fn pong(token: &str) -> String {
+    format!("PONG {token}")
+}
+
+/// Rewrite a native-format room broadcast for an IRC client.
+///
+/// Room broadcasts are plain strings shared by every subscriber regardless
+/// of dialect (`"<nick>: <text>"` for chat, `"[server] <nick> joined"` /
+/// `"[server] <nick> left."` for presence). IRC clients expect those same
+/// events as `PRIVMSG`/`JOIN`/`PART` lines, so we pattern-match the native
+/// text back into structure here rather than threading a second payload
+/// type through `Room`.
+pub fn translate_broadcast(line: &str, channel: &str) -> String {
+    if let Some(rest) = line.strip_prefix("[server] ") {
+        if let Some(nick) = rest.strip_suffix(" joined") {
+            return format_join(nick, channel);
+        }
+        if let Some(nick) = rest.strip_suffix(" joined.") {
+            return format_join(nick, channel);
+        }
+        if let Some(nick) = rest.strip_suffix(" left.") {
+            return format_part(nick, channel);
+        }
+
+        return format!(":relay NOTICE #{channel} :{rest}");
+    }
+
+    if let Some((nick, text)) = line.split_once(": ") {
+        return format_privmsg(nick, channel, text);
+    }
+
+    format!(":relay NOTICE #{channel} :{line}")
+}
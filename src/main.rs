@@ -3,14 +3,28 @@ mod state;
 mod codegen;
 mod server;
 mod conn;
+mod irc;
+mod metrics;
+mod player;
 
 use anyhow::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let listen = std::env::args().nth(1).unwrap_or_else(|| "0.0.0.0:7000".to_string());
+    let metrics_listen = std::env::args().nth(2);
 
     let state = state::ServerState::default();
 
+    if let Some(metrics_listen) = metrics_listen {
+        let metrics = state.metrics.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(&metrics_listen, metrics).await {
+                eprintln!("metrics server error: {err:?}");
+            }
+        });
+    }
+
     server::run(&listen, state).await
 }
\ No newline at end of file
@@ -0,0 +1,121 @@
+//! Minimal Prometheus metrics registry and scrape endpoint.
+//!
+//! `ServerState::metrics` tracks server-wide counters/gauges that were
+//! previously only visible as `eprintln!` lines; `serve` exposes them in
+//! the Prometheus text exposition format on a small standalone HTTP
+//! listener (see the second CLI argument in `main`).
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use anyhow::Result;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Default)]
+pub struct Metrics {
+    connected_clients: AtomicI64,
+    active_rooms: AtomicI64,
+    messages_total: AtomicU64,
+    connections_accepted_total: AtomicU64,
+    connections_dropped_total: AtomicU64
+}
+
+impl Metrics {
+    pub fn inc_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        self.connections_accepted_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_connected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_dropped(&self) {
+        self.connections_dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_messages(&self) {
+        self.messages_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_active_rooms(&self, n: usize) {
+        self.active_rooms.store(n as i64, Ordering::Relaxed);
+    }
+
+    /// Render the registry in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        format!(
+            "# HELP relay_connected_clients Number of clients currently connected.\n\
+             # TYPE relay_connected_clients gauge\n\
+             relay_connected_clients {}\n\
+             # HELP relay_active_rooms Number of rooms currently active.\n\
+             # TYPE relay_active_rooms gauge\n\
+             relay_active_rooms {}\n\
+             # HELP relay_messages_total Total number of messages relayed.\n\
+             # TYPE relay_messages_total counter\n\
+             relay_messages_total {}\n\
+             # HELP relay_connections_accepted_total Total number of connections accepted.\n\
+             # TYPE relay_connections_accepted_total counter\n\
+             relay_connections_accepted_total {}\n\
+             # HELP relay_connections_dropped_total Total number of connections dropped due to errors.\n\
+             # TYPE relay_connections_dropped_total counter\n\
+             relay_connections_dropped_total {}\n",
+            self.connected_clients.load(Ordering::Relaxed),
+            self.active_rooms.load(Ordering::Relaxed),
+            self.messages_total.load(Ordering::Relaxed),
+            self.connections_accepted_total.load(Ordering::Relaxed),
+            self.connections_dropped_total.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Serve the metrics registry over a tiny HTTP listener, responding to
+/// `GET /metrics` with the Prometheus text exposition format.
+pub async fn serve(listen_addr: &str, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(listen_addr).await?;
+
+    eprintln!("metrics listening on {}", listen_addr);
+
+    loop {
+        let (socket, _peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle_scrape(socket, metrics).await {
+                eprintln!("metrics connection error: {err:?}");
+            }
+        });
+    }
+}
+
+async fn handle_scrape(socket: TcpStream, metrics: Arc<Metrics>) -> Result<()> {
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let request_line = lines.next_line().await?.unwrap_or_default();
+
+    let body = if request_line.starts_with("GET /metrics") {
+        metrics.render()
+    } else {
+        "not found\n".to_string()
+    };
+
+    let status = if request_line.starts_with("GET /metrics") {
+        "200 OK"
+    } else {
+        "404 Not Found"
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Stable per-nickname identity.
+//!
+//! A nick can be held by several live connections at once (multiple
+//! devices/tabs for the same user), but never by two different identities
+//! at the same time. `PlayerRegistry` tracks that: each nick maps to a
+//! [`PlayerHandle`] holding the set of connection IDs currently using it.
+
+use std::sync::Arc;
+
+use dashmap::{DashMap, DashSet};
+
+/// Identifies a single TCP connection, assigned by `ServerState::next_conn_id`.
+pub type ConnId = u64;
+
+#[derive(Clone, Default)]
+pub struct PlayerHandle {
+    connections: Arc<DashSet<ConnId>>
+}
+
+impl PlayerHandle {
+    fn new() -> Self {
+        Self { connections: Arc::new(DashSet::new()) }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PlayerRegistry {
+    players: Arc<DashMap<String, PlayerHandle>>
+}
+
+impl PlayerRegistry {
+    /// Reserve `nick` for `conn_id`.
+    ///
+    /// Succeeds if nobody holds the nick yet, or if `conn_id` already holds
+    /// it (so re-sending `NICK` with the same name is a no-op). Fails if a
+    /// different identity is currently using it.
+    pub fn reserve(&self, nick: &str, conn_id: ConnId) -> Result<(), String> {
+        let key = nick.to_lowercase();
+        let handle = self.players.entry(key).or_insert_with(PlayerHandle::new);
+
+        if handle.connections.is_empty() || handle.connections.contains(&conn_id) {
+            handle.connections.insert(conn_id);
+            Ok(())
+        } else {
+            Err("nickname in use".to_string())
+        }
+    }
+
+    /// Release `conn_id`'s hold on `nick`, dropping the identity entirely
+    /// once its last connection is gone.
+    ///
+    /// Returns `true` when this was the last connection holding `nick`, so
+    /// callers can also tear down anything else tied to the identity (e.g.
+    /// its whisper channel).
+    pub fn release(&self, nick: &str, conn_id: ConnId) -> bool {
+        let key = nick.to_lowercase();
+
+        if let Some(handle) = self.players.get(&key) {
+            handle.connections.remove(&conn_id);
+            drop(handle);
+
+            // Re-check emptiness under the write lock, so a `reserve` that
+            // slipped in after the removal above keeps its entry.
+            return self
+                .players
+                .remove_if(&key, |_, handle| handle.connections.is_empty())
+                .is_some();
+        }
+
+        false
+    }
+}
@@ -5,7 +5,10 @@ pub enum Command {
     Nick(String),
     Create,
     Join(String),
-    Msg(String)
+    Msg(String),
+    Whisper(String, String),
+    List,
+    Who(Option<String>)
 }
 
 pub fn parse_command(line: &str) -> Result<Command, String> {
@@ -37,8 +40,85 @@ pub fn parse_command(line: &str) -> Result<Command, String> {
 
             Ok(Command::Msg(text.to_string()))
         },
+        "WHISPER" => {
+            let args = rest.ok_or("usage: WHISPER <nick> <text>")?;
+
+            let mut args = args.splitn(2, ' ');
+            let target = args.next().ok_or("usage: WHISPER <nick> <text>")?;
+            let text = args.next().ok_or("usage: WHISPER <nick> <text>")?;
+
+            Ok(Command::Whisper(target.to_string(), text.to_string()))
+        },
+        "LIST" => Ok(Command::List),
+        "WHO" => Ok(Command::Who(rest.map(|code| code.to_uppercase()))),
         _ => Err(format!("unknown command: {}", cmd))
     }
 
 
+}
+
+/// A command in the standard IRC line protocol (RFC 1459-ish subset).
+///
+/// Parsed separately from [`Command`] so a connection can be driven by
+/// either dialect; see `conn::handle` for how a connection picks one.
+#[derive(Debug)]
+pub enum IrcCommand {
+    Nick(String),
+    User(String),
+    Join(String),
+    Privmsg(String, String),
+    Part(String),
+    Quit,
+    Ping(String)
+}
+
+pub fn parse_irc_command(line: &str) -> Result<IrcCommand, String> {
+    let mut parts = line.trim().splitn(2, ' ');
+
+    let cmd = parts.next().unwrap_or("").to_uppercase();
+
+    let rest = parts.next().map(str::trim);
+
+    match cmd.as_str() {
+        "NICK" => {
+            let name = rest.ok_or("usage: NICK <name>")?;
+            if name.is_empty() {
+                return Err("nickname cannot be empty".into());
+            }
+
+            Ok(IrcCommand::Nick(name.to_string()))
+        },
+        "USER" => {
+            let args = rest.ok_or("usage: USER <user> <mode> <unused> :<realname>")?;
+            let username = args.split_whitespace().next().unwrap_or(args);
+
+            Ok(IrcCommand::User(username.to_string()))
+        },
+        "JOIN" => {
+            let chan = rest.ok_or("usage: JOIN #<channel>")?;
+
+            Ok(IrcCommand::Join(chan.trim_start_matches('#').to_uppercase()))
+        },
+        "PRIVMSG" => {
+            let args = rest.ok_or("usage: PRIVMSG <target> :<text>")?;
+
+            let mut args = args.splitn(2, " :");
+            let target = args.next().unwrap_or("").trim_start_matches('#').to_uppercase();
+            let text = args.next().ok_or("usage: PRIVMSG <target> :<text>")?;
+
+            Ok(IrcCommand::Privmsg(target, text.to_string()))
+        },
+        "PART" => {
+            let chan = rest.ok_or("usage: PART #<channel>")?;
+
+            Ok(IrcCommand::Part(chan.trim_start_matches('#').to_uppercase()))
+        },
+        "QUIT" => Ok(IrcCommand::Quit),
+        "PING" => {
+            let token = rest.ok_or("usage: PING <token>")?;
+
+            Ok(IrcCommand::Ping(token.to_string()))
+        },
+        _ => Err(format!("unknown IRC command: {}", cmd))
+    }
 }
\ No newline at end of file
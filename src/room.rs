@@ -1,42 +1,108 @@
-use std::sync::{
-    Arc,
-    atomic::{AtomicUsize, Ordering}
-};
+use dashmap::DashMap;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use tokio::sync::broadcast;
 
+use crate::player::ConnId;
+
+/// How many lines of history a newly joined client is replayed.
+const HISTORY_CAPACITY: usize = 50;
+
+/// A broadcast line tagged with the connection that caused it, so a
+/// subscriber can tell its own messages apart from everyone else's (e.g. to
+/// suppress IRC self-echo) without parsing the rendered text.
+#[derive(Clone)]
+pub struct RoomEvent {
+    pub origin: ConnId,
+    pub text: String
+}
+
 #[derive(Clone)]
 pub struct Room {
-    tx: broadcast::Sender<String>,
-    users: Arc<AtomicUsize>
+    tx: broadcast::Sender<RoomEvent>,
+    /// Keyed by connection rather than nick - several connections can share
+    /// a nick, and one of them leaving must not evict the others.
+    members: Arc<DashMap<ConnId, String>>,
+    history: Arc<Mutex<VecDeque<String>>>
 }
 
 impl Room {
     pub fn new(capacity: usize) -> Self {
-        let (tx, _rx) = broadcast::channel::<String>(capacity);
+        let (tx, _rx) = broadcast::channel::<RoomEvent>(capacity);
 
         Self {
             tx,
-            users: Arc::new(AtomicUsize::new(0))
+            members: Arc::new(DashMap::new()),
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY_CAPACITY)))
         }
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<String> {
-        self.tx.subscribe()
+    /// Subscribe and snapshot history as one atomic step.
+    ///
+    /// Taking the history lock across both means a message broadcast
+    /// concurrently with a join is delivered to the joiner exactly once -
+    /// either it lands in the snapshot (broadcast happened first) or it
+    /// arrives live on the new receiver (subscribe happened first) - instead
+    /// of a `subscribe()` followed by a separate `history()` call racing a
+    /// `send`/`announce` and landing in both.
+    pub fn subscribe_with_history(&self) -> (broadcast::Receiver<RoomEvent>, Vec<String>) {
+        let history = self.history.lock().unwrap();
+        let rx = self.tx.subscribe();
+        let snapshot = history.iter().cloned().collect();
+
+        (rx, snapshot)
     }
 
-    pub fn send<S: Into<String>>(&self, msg: S) {
-        let _ = self.tx.send(msg.into());
+    /// Broadcast a chat line from `origin`, recording it in history for
+    /// replay to future joiners.
+    pub fn send<S: Into<String>>(&self, origin: ConnId, msg: S) {
+        let text = msg.into();
+        let mut history = self.history.lock().unwrap();
+
+        if history.len() == HISTORY_CAPACITY {
+            history.pop_front();
+        }
+        history.push_back(text.clone());
+
+        let _ = self.tx.send(RoomEvent { origin, text });
     }
 
-    pub fn inc(&self) {
-        self.users.fetch_add(1, Ordering::Relaxed);
+    /// Broadcast a presence line (join/leave) from `origin` without
+    /// recording it in history - joiners replay what was said, not who came
+    /// and went.
+    pub fn announce<S: Into<String>>(&self, origin: ConnId, msg: S) {
+        let _history = self.history.lock().unwrap();
+        let _ = self.tx.send(RoomEvent { origin, text: msg.into() });
     }
 
-    pub fn dec(&self) {
-        self.users.fetch_sub(1, Ordering::Relaxed);
+    pub fn join(&self, conn_id: ConnId, nick: &str) {
+        self.members.insert(conn_id, nick.to_string());
     }
 
+    pub fn leave(&self, conn_id: ConnId) {
+        self.members.remove(&conn_id);
+    }
+
+    /// Update the nick recorded for `conn_id`, if it's currently a member.
+    ///
+    /// Called when a connection already in the room sends `NICK`, so `WHO`
+    /// reflects the new name instead of the one captured at `join` time.
+    pub fn rename(&self, conn_id: ConnId, new_nick: &str) {
+        if let Some(mut entry) = self.members.get_mut(&conn_id) {
+            *entry = new_nick.to_string();
+        }
+    }
+
+    /// Number of connections currently in the room (not distinct nicks).
     pub fn len(&self) -> usize {
-        self.users.load(Ordering::Relaxed)
+        self.members.len()
+    }
+
+    /// Distinct nicknames currently present in the room.
+    pub fn members(&self) -> Vec<String> {
+        let mut nicks: Vec<String> = self.members.iter().map(|m| m.value().clone()).collect();
+        nicks.sort();
+        nicks.dedup();
+        nicks
     }
-}
\ No newline at end of file
+}
@@ -50,8 +50,9 @@ use crate::state::ServerState;
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on successful shutdown (never happens in normal operation),
-/// or an error if the server fails to bind or accept connections.
+/// Returns `Ok(())` once a SIGINT/SIGTERM triggers a graceful shutdown and
+/// outstanding connections have drained (or the drain timeout elapses), or
+/// an error if the server fails to bind or accept connections.
 ///
 /// # Errors
 ///
@@ -95,30 +96,83 @@ pub async fn run(listen_addr: &str, state: ServerState) -> Result<()> {
 
     eprintln!("listening on {}", listen_addr);
 
-    // Accept connections forever
+    // A `JoinSet` rather than a plain `Vec<JoinHandle>` so finished
+    // connection tasks are reaped as they complete instead of accumulating
+    // for the server's entire lifetime.
+    let mut tasks: tokio::task::JoinSet<()> = tokio::task::JoinSet::new();
+
     loop {
-        // Wait for the next client to connect
-        // Returns:
-        //   - socket: TcpStream for this specific client (Connection Socket)
-        //   - peer: client's IP address and port (e.g., "127.0.0.1:54321")
-        let (socket, peer) = listener.accept().await?;
-
-        // Clone the shared state (cheap - only clones Arc pointers)
-        // Each task needs its own handle to the shared state
-        let state = state.clone();
-
-        // Spawn a new concurrent task to handle this client
-        // The task runs independently - the main loop immediately
-        // goes back to accepting the next client
-        tokio::spawn(async move {
-            // Handle this client's connection
-            // If an error occurs, log it but don't crash the server
-            if let Err(err) = crate::conn::handle(state, socket, peer).await {
-                eprintln!("[{}] connection error: {err:?}", peer);
+        tokio::select! {
+            // Wait for the next client to connect
+            // Returns:
+            //   - socket: TcpStream for this specific client (Connection Socket)
+            //   - peer: client's IP address and port (e.g., "127.0.0.1:54321")
+            accepted = listener.accept() => {
+                let (socket, peer) = accepted?;
+
+                // Clone the shared state (cheap - only clones Arc pointers)
+                // Each task needs its own handle to the shared state
+                let state = state.clone();
+                let shutdown_rx = state.subscribe_shutdown();
+
+                // Spawn a new concurrent task to handle this client
+                // The task runs independently - the main loop immediately
+                // goes back to accepting the next client
+                tasks.spawn(async move {
+                    // Handle this client's connection
+                    // If an error occurs, log it but don't crash the server
+                    if let Err(err) = crate::conn::handle(state.clone(), socket, peer, shutdown_rx).await {
+                        state.metrics.inc_dropped();
+                        eprintln!("[{}] connection error: {err:?}", peer);
+                    }
+                });
+
+                // Loop continues immediately - listener is still open and ready
+                // for the next client to connect
             }
-        });
 
-        // Loop continues immediately - listener is still open and ready
-        // for the next client to connect
+            // Reap a connection task as soon as it finishes, so a
+            // long-running server doesn't hold onto a handle per
+            // connection forever.
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+
+            // Cooperative shutdown: stop accepting and tell every connection
+            // task to drain once SIGINT/SIGTERM arrives.
+            _ = wait_for_shutdown_signal() => {
+                eprintln!("shutdown requested, no longer accepting connections");
+                let _ = state.shutdown.send(());
+                break;
+            }
+        }
+    }
+
+    let drain = async {
+        while tasks.join_next().await.is_some() {}
+    };
+
+    if tokio::time::timeout(std::time::Duration::from_secs(5), drain).await.is_err() {
+        eprintln!("timed out waiting for connections to drain");
+    }
+
+    Ok(())
+}
+
+/// Resolve once the process receives SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
     }
 }
\ No newline at end of file
@@ -1,28 +1,127 @@
 use dashmap::DashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast;
 
+use crate::metrics::Metrics;
+use crate::player::{ConnId, PlayerRegistry};
 use crate::room::Room;
 
-#[derive(Clone, Default)]
+/// Connections never see a payload on `shutdown` - the channel is only used
+/// to wake every `conn::handle` task and tell it to drain.
+const SHUTDOWN_CAPACITY: usize = 1;
+
+/// Depth of a nick's personal whisper channel - deep enough to absorb a
+/// burst of DMs between the sender's broadcast and the recipient draining it.
+const WHISPER_CAPACITY: usize = 64;
+
+#[derive(Clone)]
 pub struct ServerState {
-    pub rooms: Arc<DashMap<String, Room>>
+    pub rooms: Arc<DashMap<String, Room>>,
+    pub metrics: Arc<Metrics>,
+    pub shutdown: broadcast::Sender<()>,
+    pub players: PlayerRegistry,
+    whispers: Arc<DashMap<String, broadcast::Sender<String>>>,
+    conn_ids: Arc<AtomicU64>
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        let (shutdown, _rx) = broadcast::channel(SHUTDOWN_CAPACITY);
+
+        Self {
+            rooms: Arc::new(DashMap::new()),
+            metrics: Arc::new(Metrics::default()),
+            shutdown,
+            players: PlayerRegistry::default(),
+            whispers: Arc::new(DashMap::new()),
+            conn_ids: Arc::new(AtomicU64::new(1))
+        }
+    }
 }
 
 impl ServerState {
+    pub fn subscribe_shutdown(&self) -> broadcast::Receiver<()> {
+        self.shutdown.subscribe()
+    }
+
+    /// Assign a fresh connection ID, used to key `PlayerRegistry` reservations.
+    pub fn next_conn_id(&self) -> ConnId {
+        self.conn_ids.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Fetch (creating if needed) the personal whisper channel for `nick`.
+    ///
+    /// Called when a connection sets its nick, so the channel exists before
+    /// anyone can `WHISPER` it.
+    pub fn ensure_whisper_channel(&self, nick: &str) -> broadcast::Sender<String> {
+        self.whispers
+            .entry(nick.to_lowercase())
+            .or_insert_with(|| broadcast::channel(WHISPER_CAPACITY).0)
+            .clone()
+    }
+
+    /// Look up the whisper channel for `nick`, if anyone has claimed it and
+    /// is still listening on it.
+    pub fn whisper_sender(&self, nick: &str) -> Option<broadcast::Sender<String>> {
+        let tx = self.whispers.get(&nick.to_lowercase())?.clone();
+
+        if tx.receiver_count() == 0 {
+            return None;
+        }
+
+        Some(tx)
+    }
+
+    /// Drop the whisper channel for `nick`, called once its last connection
+    /// has released the identity (see `PlayerRegistry::release`).
+    pub fn remove_whisper_channel(&self, nick: &str) {
+        self.whispers.remove(&nick.to_lowercase());
+    }
+
 
     pub fn insert_room(&self, code: String, room: Room) {
         self.rooms.insert(code, room);
+        self.metrics.set_active_rooms(self.rooms.len());
     }
 
     pub fn get_room(&self, code: &str) -> Option<Room> {
         self.rooms.get(code).map(|guard| guard.clone())
     }
 
+    /// Fetch the room named `code`, creating an empty one if it doesn't exist yet.
+    ///
+    /// Used by the IRC translation layer, where `JOIN #<chan>` always succeeds
+    /// instead of requiring a prior `CREATE` like the native protocol does.
+    ///
+    /// Uses `entry`/`or_insert_with` so two concurrent first-joiners can't
+    /// each create their own `Room` and end up subscribed to different
+    /// instances - only one `Room` is ever inserted for a given code.
+    pub fn get_or_create_room(&self, code: &str) -> Room {
+        let mut inserted = false;
+
+        let room = self
+            .rooms
+            .entry(code.to_string())
+            .or_insert_with(|| {
+                inserted = true;
+                Room::new(512)
+            })
+            .clone();
+
+        if inserted {
+            self.metrics.set_active_rooms(self.rooms.len());
+        }
+
+        room
+    }
+
     pub fn remove_if_empty(&self, code: &str) {
         if let Some(r) = self.rooms.get(code) {
             if r.len() == 0 {
                 drop(r);
                 self.rooms.remove(code);
+                self.metrics.set_active_rooms(self.rooms.len());
             }
         }
     }